@@ -1,24 +1,31 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{Read, Write},
+    io::{Read, Seek, SeekFrom, Write},
     net::{TcpListener, TcpStream},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
     sync::{
+        atomic::{AtomicBool, Ordering},
         mpsc::{self, Receiver, Sender},
         Arc, Mutex,
     },
     thread,
+    time::Duration,
 };
 
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use itertools::Itertools;
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
-#[allow(dead_code)]
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
 struct ThreadPool {
     workers: Vec<Worker>,
-    sender: Sender<Job>,
+    sender: Sender<Message>,
 }
 
 impl ThreadPool {
@@ -36,71 +43,146 @@ impl ThreadPool {
 
     fn execute<F: FnOnce() + Send + 'static>(&self, function: F) {
         let job = Box::new(function);
-        self.sender.send(job).unwrap();
+        self.sender.send(Message::NewJob(job)).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            // A send error means every worker has already exited (e.g. handler
+            // panics tore them all down, and the last one dropped the shared
+            // receiver); there's nothing left to tell, so there's nothing to do about it.
+            let _ = self.sender.send(Message::Terminate);
+        }
+
+        for worker in &mut self.workers {
+            println!("shutting down worker {}", worker.id);
+            if let Some(handle) = worker.handle.take()
+                && handle.join().is_err()
+            {
+                println!("worker {} panicked before shutting down", worker.id);
+            }
+        }
     }
 }
 
-#[allow(dead_code)]
 struct Worker {
     id: usize,
-    handle: std::thread::JoinHandle<()>,
+    handle: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
+    fn new(id: usize, receiver: Arc<Mutex<Receiver<Message>>>) -> Self {
         let handle = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
-            println!("Worker {id} got a job; executing.");
-
-            job();
+            let message = receiver.lock().unwrap().recv().unwrap();
+
+            match message {
+                Message::NewJob(job) => {
+                    println!("Worker {id} got a job; executing.");
+                    job();
+                }
+                Message::Terminate => break,
+            }
         });
-        Self { id, handle }
+        Self {
+            id,
+            handle: Some(handle),
+        }
     }
 }
 
-struct Request<'a> {
-    method: &'a str,
-    path: &'a str,
-    _version: &'a str,
-    headers: HashMap<String, &'a str>,
-    body: &'a str,
+struct Request {
+    method: String,
+    path: String,
+    _version: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
 }
 
-impl<'a> Request<'a> {
-    fn from_str(s: &'a str) -> Self {
-        let (status_line, rest) = s.split_once("\r\n").unwrap();
+impl Request {
+    /// Reads a request off `stream` incrementally: the status line and headers are read
+    /// byte-by-chunk until the `\r\n\r\n` terminator, then `Content-Length` (if any) tells
+    /// us exactly how many more body bytes to pull. This lets bodies of arbitrary size and
+    /// binary content survive, unlike reading a single fixed-size buffer up front.
+    fn read_from(stream: &mut TcpStream) -> std::io::Result<Self> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 512];
+
+        let header_end = loop {
+            if let Some(pos) = find_subslice(&buffer, b"\r\n\r\n") {
+                break pos;
+            }
+
+            let n_read = stream.read(&mut chunk)?;
+            if n_read == 0 {
+                // The peer closed the connection (an idle timeout instead surfaces as
+                // an error from `read` above). An empty buffer just means "no more
+                // requests"; a partial one is a malformed request either way, so both
+                // end the connection.
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed before a full request was received",
+                ));
+            }
+            buffer.extend_from_slice(&chunk[..n_read]);
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..header_end]);
+        let (status_line, rest) = head.split_once("\r\n").unwrap_or((&head, ""));
 
-        let [method, path, _version]: [&str; 3] = status_line
+        let [method, path, version]: [&str; 3] = status_line
             .split_whitespace()
             .collect::<Vec<_>>()
             .try_into()
             .unwrap();
 
-        let (string_headers, body) = rest.split_once("\r\n\r\n").unwrap_or_default();
-        let headers = string_headers
+        let headers = rest
             .split("\r\n")
+            .filter(|line| !line.is_empty())
             .map(|line| {
                 line.split_once(": ")
-                    .map(|(h, c)| (h.to_lowercase(), c))
+                    .map(|(h, c)| (h.to_lowercase(), c.to_string()))
                     .unwrap()
             })
             .collect::<HashMap<_, _>>();
 
-        Request {
-            method,
-            path,
-            _version,
+        let content_length = headers
+            .get("content-length")
+            .and_then(|len| len.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        let mut body = buffer.get(header_end + 4..).unwrap_or_default().to_vec();
+        while body.len() < content_length {
+            let n_read = stream.read(&mut chunk)?;
+            if n_read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..n_read]);
+        }
+        body.truncate(content_length);
+
+        Ok(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            _version: version.to_string(),
             headers,
             body,
-        }
+        })
     }
 }
 
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
 #[derive(Debug, Default)]
 struct Response<'a> {
     status_line: &'a str,
     headers: HashMap<&'a str, String>,
-    body: String,
+    body: Vec<u8>,
 }
 
 impl<'a> Response<'a> {
@@ -111,17 +193,136 @@ impl<'a> Response<'a> {
         }
     }
 
-    fn build(&self) -> String {
+    fn build(&self) -> Vec<u8> {
         let headers: String = self
             .headers
             .iter()
             .map(|(k, v)| format!("{k}: {v}\r\n"))
             .join("");
 
-        format!("{}\r\n{}\r\n{}", self.status_line, headers, self.body)
+        let mut bytes = format!("{}\r\n{}\r\n", self.status_line, headers).into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Gzip-compresses `self.body` in place and updates `Content-Encoding`/`Content-Length`
+    /// to match. Called once a response is final, after the handler has populated the body.
+    fn gzip_compress(&mut self) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.body).unwrap();
+        self.body = encoder.finish().unwrap();
+
+        self.headers.insert("Content-Encoding", "gzip".to_string());
+        self.headers
+            .insert("Content-Length", self.body.len().to_string());
     }
 }
 
+type Params<'a> = HashMap<&'static str, &'a str>;
+type Handler = Box<dyn Fn(&Request, &Params) -> Response<'static>>;
+
+/// Registers `(method, path-pattern)` pairs against handlers and dispatches requests to
+/// them. Patterns support named segments (`/files/{name}`) matched by splitting both the
+/// pattern and the request path on `/`; a named segment in the last position captures the
+/// rest of the path verbatim (slashes included), mirroring the old `strip_prefix` behavior
+/// for nested file paths.
+#[derive(Default)]
+struct Router {
+    routes: Vec<(&'static str, &'static str, Handler)>,
+}
+
+impl Router {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn register<F>(&mut self, method: &'static str, pattern: &'static str, handler: F)
+    where
+        F: Fn(&Request, &Params) -> Response<'static> + 'static,
+    {
+        self.routes.push((method, pattern, Box::new(handler)));
+    }
+
+    fn dispatch(&self, request: &Request) -> Response<'static> {
+        let mut path_matched = false;
+
+        for (method, pattern, handler) in &self.routes {
+            let Some(params) = match_path(pattern, &request.path) else {
+                continue;
+            };
+
+            if *method != request.method {
+                path_matched = true;
+                continue;
+            }
+
+            return handler(request, &params);
+        }
+
+        if path_matched {
+            Response::new("HTTP/1.1 405 Method Not Allowed")
+        } else {
+            Response::new("HTTP/1.1 404 Not Found")
+        }
+    }
+}
+
+fn match_path<'p>(pattern: &'static str, path: &'p str) -> Option<Params<'p>> {
+    let segments = pattern
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty());
+
+    let mut params = Params::new();
+    let mut rest = path.trim_matches('/');
+
+    let mut segments = segments.peekable();
+    while let Some(segment) = segments.next() {
+        let is_last = segments.peek().is_none();
+
+        if let Some(name) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            if is_last {
+                params.insert(name, rest);
+                rest = "";
+            } else {
+                let (value, remainder) = rest.split_once('/')?;
+                params.insert(name, value);
+                rest = remainder;
+            }
+        } else {
+            let (value, remainder) = rest.split_once('/').unwrap_or((rest, ""));
+            if value != segment {
+                return None;
+            }
+            rest = remainder;
+        }
+    }
+
+    rest.is_empty().then_some(params)
+}
+
+fn build_router(files_directory: PathBuf) -> Router {
+    let mut router = Router::new();
+
+    router.register("GET", "/", |_request, _params| Response::new("HTTP/1.1 200 OK"));
+    router.register("GET", "/user-agent", |request, _params| {
+        handle_get_user_agent(request)
+    });
+    router.register("GET", "/echo/{text}", |_request, params| {
+        handle_get_echo(params["text"])
+    });
+
+    let get_directory = files_directory.clone();
+    router.register("GET", "/files/{name}", move |request, params| {
+        handle_get_files(request, params["name"], get_directory.clone())
+    });
+    router.register("POST", "/files/{name}", move |request, params| {
+        handle_post_files(request, params["name"], files_directory.clone())
+    });
+
+    router
+}
+
 fn main() {
     let mut args = std::env::args();
 
@@ -137,11 +338,20 @@ fn main() {
     }
 
     let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    listener.set_nonblocking(true).unwrap();
     let pool = ThreadPool::new(10);
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_handler = shutdown.clone();
+    ctrlc::set_handler(move || {
+        println!("shutting down, draining in-flight connections...");
+        shutdown_handler.store(true, Ordering::SeqCst);
+    })
+    .unwrap();
+
+    while !shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
                 println!("accepted new connection");
 
                 let directory_path = directory_path.clone();
@@ -149,48 +359,81 @@ fn main() {
                     handle_connection(stream, directory_path);
                 });
             }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
             Err(e) => {
                 println!("error: {}", e);
             }
         }
     }
+
+    drop(pool);
 }
 
+/// How long a connection may sit idle between requests before we give up on it.
+const KEEP_ALIVE_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
 fn handle_connection(mut stream: TcpStream, files_directory: PathBuf) {
-    let mut buffer = [0; 256];
-    let n_read = stream.read(&mut buffer).unwrap();
+    stream
+        .set_read_timeout(Some(KEEP_ALIVE_IDLE_TIMEOUT))
+        .unwrap();
 
-    let request_str = std::str::from_utf8(&buffer[0..n_read]).unwrap();
-    let request = Request::from_str(request_str);
+    let router = build_router(files_directory);
 
-    let mut response = match request.path {
-        "/" => Response::new("HTTP/1.1 200 OK"),
-        "/user-agent" => handle_get_user_agent(&request),
-        p if p.starts_with("/files/") => {
-            let relative_path = p.strip_prefix("/files/").unwrap();
-            match request.method {
-                "GET" => handle_get_files(&request, relative_path, files_directory),
-                "POST" => handle_post_files(&request, relative_path, files_directory),
-                _ => unimplemented!(),
-            }
+    loop {
+        let request = match Request::read_from(&mut stream) {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+
+        let wants_close = request
+            .headers
+            .get("connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+
+        let mut response = router.dispatch(&request);
+
+        let accepts_gzip = request
+            .headers
+            .get("accept-encoding")
+            .is_some_and(|encodings| encodings.split(',').map(str::trim).contains(&"gzip"));
+
+        // A partial/unsatisfiable range response's Content-Range and Content-Length
+        // describe exact byte offsets into the uncompressed resource; recompressing it
+        // would make those offsets lie about what's actually in the body.
+        let is_range_response = response.headers.contains_key("Content-Range");
+
+        if accepts_gzip && !is_range_response && !response.headers.contains_key("Content-Encoding")
+        {
+            response.gzip_compress();
         }
-        p if p.starts_with("/echo/") => handle_get_echo(&request),
-        _ => Response::new("HTTP/1.1 404 Not Found"),
-    };
 
-    if let Some(accepted_encodings) = request.headers.get("accept-encoding") {
-        if accepted_encodings.split(',').contains(&"gzip") {
-            response
-                .headers
-                .insert("Content-Encoding", "gzip".to_string());
+        response
+            .headers
+            .entry("Content-Length")
+            .or_insert_with(|| response.body.len().to_string());
+        response.headers.insert(
+            "Connection",
+            if wants_close { "close" } else { "keep-alive" }.to_string(),
+        );
+
+        if stream.write_all(&response.build()).is_err() {
+            return;
         }
-    }
 
-    stream.write_all(response.build().as_bytes()).unwrap();
+        if wants_close {
+            return;
+        }
+    }
 }
 
 fn handle_get_user_agent<'a>(request: &Request) -> Response<'a> {
-    let user_agent = request.headers.get("user-agent").unwrap_or(&"");
+    let user_agent = request
+        .headers
+        .get("user-agent")
+        .map(String::as_str)
+        .unwrap_or("");
     let len = user_agent.len();
 
     Response {
@@ -199,13 +442,12 @@ fn handle_get_user_agent<'a>(request: &Request) -> Response<'a> {
             ("Content-Type", "text/plain".to_string()),
             ("Content-Length", len.to_string()),
         ]),
-        body: user_agent.to_string(),
+        body: user_agent.as_bytes().to_vec(),
     }
 }
 
-fn handle_get_echo<'a>(request: &Request) -> Response<'a> {
-    let str = request.path.strip_prefix("/echo/").unwrap();
-    let len = str.len();
+fn handle_get_echo<'a>(text: &str) -> Response<'a> {
+    let len = text.len();
 
     Response {
         status_line: "HTTP/1.1 200 OK",
@@ -213,39 +455,461 @@ fn handle_get_echo<'a>(request: &Request) -> Response<'a> {
             ("Content-Type", "text/plain".to_string()),
             ("Content-Length", len.to_string()),
         ]),
-        body: str.to_string(),
+        body: text.as_bytes().to_vec(),
+    }
+}
+
+/// Resolves `filename` against `files_directory` without ever escaping it: `.` segments
+/// are dropped, `..` segments pop the segment before them (rejecting the request outright
+/// if that would walk above the root), and absolute paths are rejected. The joined path is
+/// built from a canonicalized root so symlink tricks in `files_directory` itself don't
+/// help either. Unlike `Path::canonicalize`, this works even when `filename` doesn't exist
+/// yet, which `handle_post_files` relies on.
+fn resolve_path(files_directory: &Path, filename: &str) -> Option<PathBuf> {
+    let root = files_directory.canonicalize().ok()?;
+    let mut relative = Vec::new();
+
+    for component in Path::new(filename).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                relative.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
     }
+
+    Some(root.join(relative.into_iter().collect::<PathBuf>()))
 }
 
 fn handle_get_files<'a>(
-    _request: &Request,
+    request: &Request,
     filename: &str,
     files_directory: PathBuf,
 ) -> Response<'a> {
-    let Ok(mut file) = File::open(files_directory.join(filename)) else {
+    let accepts_gzip = request
+        .headers
+        .get("accept-encoding")
+        .is_some_and(|encodings| encodings.split(',').map(str::trim).contains(&"gzip"));
+
+    // Listing hrefs are percent-encoded, so decode `%XX` escapes before resolving.
+    let mut decoded = Vec::with_capacity(filename.len());
+    let mut rest = filename.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|hex| byte == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        decoded.push(escaped.unwrap_or(byte));
+        rest = if escaped.is_some() { &tail[2..] } else { tail };
+    }
+    let filename = String::from_utf8_lossy(&decoded);
+
+    let Some(path) = resolve_path(&files_directory, &filename) else {
+        return Response::new("HTTP/1.1 404 Not Found");
+    };
+
+    if path.is_dir() {
+        // The listing's hrefs are relative to the request path, so `GET /files/sub`
+        // (no trailing slash) would have a browser resolve them one directory too
+        // high. Redirect to the trailing-slash form instead of serving the listing
+        // directly.
+        if !request.path.ends_with('/') {
+            return Response {
+                status_line: "HTTP/1.1 301 Moved Permanently",
+                headers: HashMap::from([("Location", format!("{}/", request.path))]),
+                ..Default::default()
+            };
+        }
+        return handle_directory_listing(&path, &files_directory);
+    }
+
+    let gz_path = {
+        let mut name = path.clone().into_os_string();
+        name.push(".gz");
+        PathBuf::from(name)
+    };
+
+    // Prefer a pre-compressed sibling when the client accepts gzip, so we never
+    // re-compress the same file on every request. A Range request has to be served
+    // from the plain file instead, since the offsets it asks for are into the
+    // uncompressed resource and we can't honor them against the gzip body.
+    let wants_range = request.headers.contains_key("range");
+    let pre_compressed = (accepts_gzip && !wants_range)
+        .then(|| File::open(&gz_path).ok())
+        .flatten();
+    if let Some(mut gz_file) = pre_compressed {
+        let mut contents = Vec::new();
+        gz_file.read_to_end(&mut contents).unwrap();
+
+        // Sniff the decompressed bytes, not the gzip body we're about to send: the
+        // MIME type is a property of the resource, not of whether this particular
+        // client negotiated gzip.
+        let mut decompressed = Vec::new();
+        GzDecoder::new(contents.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        return Response {
+            status_line: "HTTP/1.1 200 OK",
+            headers: HashMap::from([
+                ("Content-Type", content_type(&path, &decompressed).to_string()),
+                ("Content-Encoding", "gzip".to_string()),
+                ("Content-Length", contents.len().to_string()),
+            ]),
+            body: contents,
+        };
+    }
+
+    if let Ok(mut file) = File::open(&path) {
+        let total = file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+
+        return match parse_range(request.headers.get("range"), total) {
+            RangeRequest::Unsatisfiable => Response {
+                status_line: "HTTP/1.1 416 Range Not Satisfiable",
+                headers: HashMap::from([("Content-Range", format!("bytes */{total}"))]),
+                body: Vec::new(),
+            },
+            RangeRequest::Partial(start, end) => {
+                // Sniff the file's leading bytes rather than the requested slice, so
+                // every range of a resource reports the same type.
+                let mut head = vec![0; total.min(512)];
+                file.read_exact(&mut head).unwrap();
+
+                let len = end - start + 1;
+                let mut contents = vec![0; len];
+                file.seek(SeekFrom::Start(start as u64)).unwrap();
+                file.read_exact(&mut contents).unwrap();
+
+                Response {
+                    status_line: "HTTP/1.1 206 Partial Content",
+                    headers: HashMap::from([
+                        ("Content-Type", content_type(&path, &head).to_string()),
+                        ("Content-Range", format!("bytes {start}-{end}/{total}")),
+                        ("Content-Length", len.to_string()),
+                        ("Accept-Ranges", "bytes".to_string()),
+                    ]),
+                    body: contents,
+                }
+            }
+            RangeRequest::Full => {
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents).unwrap();
+
+                Response {
+                    status_line: "HTTP/1.1 200 OK",
+                    headers: HashMap::from([
+                        ("Content-Type", content_type(&path, &contents).to_string()),
+                        ("Content-Length", contents.len().to_string()),
+                        ("Accept-Ranges", "bytes".to_string()),
+                    ]),
+                    body: contents,
+                }
+            }
+        };
+    }
+
+    // The plain file doesn't exist but a compressed variant does: either serve it
+    // as-is or decompress on the fly depending on what the client can handle.
+    let Ok(mut gz_file) = File::open(&gz_path) else {
         return Response::new("HTTP/1.1 404 Not Found");
     };
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+    let mut gz_contents = Vec::new();
+    gz_file.read_to_end(&mut gz_contents).unwrap();
+
+    let mut contents = Vec::new();
+    GzDecoder::new(gz_contents.as_slice())
+        .read_to_end(&mut contents)
+        .unwrap();
+    let content_type = content_type(&path, &contents);
+
+    if accepts_gzip {
+        return Response {
+            status_line: "HTTP/1.1 200 OK",
+            headers: HashMap::from([
+                ("Content-Type", content_type.to_string()),
+                ("Content-Encoding", "gzip".to_string()),
+                ("Content-Length", gz_contents.len().to_string()),
+            ]),
+            body: gz_contents,
+        };
+    }
 
     Response {
         status_line: "HTTP/1.1 200 OK",
         headers: HashMap::from([
-            ("Content-Type", "application/octet-stream".to_string()),
+            ("Content-Type", content_type.to_string()),
             ("Content-Length", contents.len().to_string()),
         ]),
-        body: contents.to_string(),
+        body: contents,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    Full,
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+/// Parses a single `Range: bytes=start-end` header against a resource of `total` bytes.
+/// Only the first range of the header is honored; open-ended (`bytes=100-`) and suffix
+/// (`bytes=-500`) forms are both supported, per RFC 7233.
+fn parse_range(header: Option<&String>, total: usize) -> RangeRequest {
+    let Some(spec) = header.and_then(|value| value.strip_prefix("bytes=")) else {
+        return RangeRequest::Full;
+    };
+    let Some((start, end)) = spec.split(',').next().unwrap_or(spec).split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let range = match (start, end) {
+        ("", suffix) => suffix.parse::<usize>().ok().and_then(|suffix_len| {
+            (suffix_len > 0 && total > 0).then(|| (total.saturating_sub(suffix_len), total - 1))
+        }),
+        (start, "") => start
+            .parse::<usize>()
+            .ok()
+            .map(|start| (start, total.saturating_sub(1))),
+        (start, end) => start
+            .parse::<usize>()
+            .ok()
+            .zip(end.parse::<usize>().ok()),
+    };
+
+    match range {
+        Some((start, end)) if total > 0 && start < total && start <= end => {
+            RangeRequest::Partial(start, end.min(total - 1))
+        }
+        _ => RangeRequest::Unsatisfiable,
     }
 }
 
+/// Resolves a MIME type for `path`: known extensions map directly, otherwise `head`
+/// (the file's leading bytes) is sniffed to tell UTF-8 text from arbitrary binary data.
+fn content_type(path: &Path, head: &[u8]) -> &'static str {
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        let mime = match extension.to_ascii_lowercase().as_str() {
+            "html" | "htm" => Some("text/html"),
+            "css" => Some("text/css"),
+            "js" => Some("text/javascript"),
+            "json" => Some("application/json"),
+            "png" => Some("image/png"),
+            "jpg" | "jpeg" => Some("image/jpeg"),
+            "gif" => Some("image/gif"),
+            "svg" => Some("image/svg+xml"),
+            "txt" => Some("text/plain"),
+            "pdf" => Some("application/pdf"),
+            _ => None,
+        };
+        if let Some(mime) = mime {
+            return mime;
+        }
+    }
+
+    let sample = &head[..head.len().min(512)];
+    match std::str::from_utf8(sample) {
+        Ok(_) => "text/plain",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+/// Lists the contents of `dir` as an HTML index. `files_directory` is the configured
+/// root; the joined path is canonicalized and checked against it so `../` segments in
+/// the request can't walk the listing outside of the served tree.
+fn handle_directory_listing<'a>(dir: &Path, files_directory: &Path) -> Response<'a> {
+    let Ok(canonical_dir) = dir.canonicalize() else {
+        return Response::new("HTTP/1.1 404 Not Found");
+    };
+    let Ok(canonical_root) = files_directory.canonicalize() else {
+        return Response::new("HTTP/1.1 404 Not Found");
+    };
+    if !canonical_dir.starts_with(canonical_root) {
+        return Response::new("HTTP/1.1 404 Not Found");
+    }
+
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return Response::new("HTTP/1.1 404 Not Found");
+    };
+
+    let mut entries = read_dir.filter_map(Result::ok).collect::<Vec<_>>();
+    entries.sort_by(|a, b| {
+        let a_is_dir = a.path().is_dir();
+        let b_is_dir = b.path().is_dir();
+        b_is_dir
+            .cmp(&a_is_dir)
+            .then_with(|| a.file_name().cmp(&b.file_name()))
+    });
+
+    let list_items = entries
+        .iter()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_dir = entry.path().is_dir();
+            let suffix = if is_dir { "/" } else { "" };
+
+            format!(
+                "<li><a href=\"{}{suffix}\">{}{suffix}</a></li>",
+                percent_encode(&name),
+                html_escape(&name)
+            )
+        })
+        .join("");
+
+    let body = format!("<html><body><ul>{list_items}</ul></body></html>");
+    let len = body.len();
+
+    Response {
+        status_line: "HTTP/1.1 200 OK",
+        headers: HashMap::from([
+            ("Content-Type", "text/html".to_string()),
+            ("Content-Length", len.to_string()),
+        ]),
+        body: body.into_bytes(),
+    }
+}
+
+/// Percent-encodes everything but unreserved URL characters, so filenames with spaces
+/// or other special characters still work as link targets.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Escapes the characters HTML treats as markup, so untrusted filenames can't inject
+/// tags when interpolated into the directory listing's anchor text.
+fn html_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
 fn handle_post_files<'a>(
     request: &Request,
     filename: &str,
     files_directory: PathBuf,
 ) -> Response<'a> {
-    let mut file = File::create(files_directory.join(filename)).unwrap();
-    file.write_all(request.body.as_bytes()).unwrap();
+    let Some(path) = resolve_path(&files_directory, filename) else {
+        return Response::new("HTTP/1.1 404 Not Found");
+    };
+
+    let mut file = File::create(path).unwrap();
+    file.write_all(&request.body).unwrap();
 
     Response::new("HTTP/1.1 201 Created")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_escape_escapes_markup() {
+        assert_eq!(
+            html_escape("<b>XSS</b> & \"quotes\" 'n stuff"),
+            "&lt;b&gt;XSS&lt;/b&gt; &amp; &quot;quotes&quot; &#39;n stuff"
+        );
+    }
+
+    #[test]
+    fn match_path_root() {
+        let params = match_path("/", "/").unwrap();
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn match_path_static_segments() {
+        assert!(match_path("/user-agent", "/user-agent").is_some());
+        assert!(match_path("/user-agent", "/other").is_none());
+    }
+
+    #[test]
+    fn match_path_named_segment() {
+        let params = match_path("/echo/{text}", "/echo/hello").unwrap();
+        assert_eq!(params.get("text"), Some(&"hello"));
+    }
+
+    #[test]
+    fn match_path_trailing_capture_keeps_embedded_slashes() {
+        let params = match_path("/files/{name}", "/files/a/b/c.txt").unwrap();
+        assert_eq!(params.get("name"), Some(&"a/b/c.txt"));
+    }
+
+    #[test]
+    fn match_path_mismatched_segment_count() {
+        assert!(match_path("/files/{name}/edit", "/files/a.txt").is_none());
+        assert!(match_path("/user-agent", "/user-agent/extra").is_none());
+    }
+
+    #[test]
+    fn parse_range_missing_header_is_full() {
+        assert_eq!(parse_range(None, 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn parse_range_malformed_header_is_full() {
+        let header = "not-bytes=0-9".to_string();
+        assert_eq!(parse_range(Some(&header), 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn parse_range_start_end() {
+        let header = "bytes=0-9".to_string();
+        assert_eq!(
+            parse_range(Some(&header), 100),
+            RangeRequest::Partial(0, 9)
+        );
+    }
+
+    #[test]
+    fn parse_range_open_ended() {
+        let header = "bytes=90-".to_string();
+        assert_eq!(
+            parse_range(Some(&header), 100),
+            RangeRequest::Partial(90, 99)
+        );
+    }
+
+    #[test]
+    fn parse_range_suffix() {
+        let header = "bytes=-10".to_string();
+        assert_eq!(
+            parse_range(Some(&header), 100),
+            RangeRequest::Partial(90, 99)
+        );
+    }
+
+    #[test]
+    fn parse_range_out_of_bounds_is_unsatisfiable() {
+        let header = "bytes=200-300".to_string();
+        assert_eq!(
+            parse_range(Some(&header), 100),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn parse_range_end_past_total_is_clamped() {
+        let header = "bytes=0-1000".to_string();
+        assert_eq!(
+            parse_range(Some(&header), 100),
+            RangeRequest::Partial(0, 99)
+        );
+    }
+}